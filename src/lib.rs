@@ -1,9 +1,13 @@
+use std::collections::HashMap;
+
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use schemars::JsonSchema;
+use near_sdk::json_types::{Base64VecU8, U128};
 use near_sdk::{
-    env, near_bindgen, AccountId, BorshStorageKey, PanicOnDefault,
+    assert_one_yocto, env, near_bindgen, AccountId, BorshStorageKey, PanicOnDefault, Promise,
     collections::{LookupMap, UnorderedSet, Vector},
     serde::{Deserialize, Serialize},
+    serde_json::json,
 };
 
 #[near_bindgen]
@@ -42,6 +46,40 @@ pub struct MemeNFT {
     pub likes_count: u32,
     pub comments_count: u32,
     pub last_like_timestamp: u64,
+    pub ipfs_cid: Option<String>,
+}
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE32_ALPHABET: &str = "abcdefghijklmnopqrstuvwxyz234567";
+
+fn validate_ipfs_cid(cid: &str) {
+    let is_valid = if let Some(rest) = cid.strip_prefix("Qm") {
+        cid.len() == 46 && rest.chars().all(|c| BASE58_ALPHABET.contains(c))
+    } else if cid.starts_with("bafy") {
+        cid.len() >= 59 && cid.chars().all(|c| BASE32_ALPHABET.contains(c))
+    } else {
+        false
+    };
+    assert!(
+        is_valid,
+        "Invalid IPFS CID: expected a CIDv0 (Qm..., base58, 46 chars) or CIDv1 (bafy..., base32)"
+    );
+}
+
+/// Slices an already-filtered result vector by `from_index`/`limit` using the same `u64`
+/// range arithmetic `get_all_memes` uses directly on a `Vector`, so a caller-supplied
+/// `from_index` beyond `u32::MAX` can't truncate into a small `usize` on wasm32.
+fn paginate<T>(items: Vec<T>, from_index: u64, limit: u64) -> Vec<T> {
+    let len = items.len() as u64;
+    let end = std::cmp::min(from_index.saturating_add(limit), len);
+    if from_index >= end {
+        return Vec::new();
+    }
+    items
+        .into_iter()
+        .skip(from_index as usize)
+        .take((end - from_index) as usize)
+        .collect()
 }
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, JsonSchema)]
@@ -60,6 +98,71 @@ pub struct UserStats {
     pub total_earnings: u128,
 }
 
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ListingInfo {
+    pub meme: MemeNFT,
+    pub price: u128,
+}
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NFTContractMetadata {
+    pub spec: String,
+    pub name: String,
+    pub symbol: String,
+    pub icon: Option<String>,
+    pub base_uri: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<Base64VecU8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenMetadata {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub media: Option<String>,
+    pub media_hash: Option<Base64VecU8>,
+    pub copies: Option<u64>,
+    pub issued_at: Option<String>,
+    pub expires_at: Option<String>,
+    pub starts_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub extra: Option<String>,
+    pub reference: Option<String>,
+    pub reference_hash: Option<Base64VecU8>,
+}
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Token {
+    pub token_id: String,
+    pub owner_id: AccountId,
+    pub metadata: Option<TokenMetadata>,
+    pub approved_account_ids: Option<HashMap<AccountId, u64>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Payout {
+    pub payout: HashMap<AccountId, U128>,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, JsonSchema)]
+#[serde(crate = "near_sdk::serde")]
+pub enum MatchMode {
+    Exact,
+    Contains,
+    StartsWith,
+}
+
+impl Default for MatchMode {
+    fn default() -> Self {
+        MatchMode::Contains
+    }
+}
+
 #[near_bindgen]
 impl MemeFiContract {
     #[init]
@@ -82,6 +185,7 @@ impl MemeFiContract {
         title: String,
         description: String,
         royalty: u8,
+        ipfs_cid: Option<String>,
     ) {
         let creator = env::predecessor_account_id().to_string();
         assert!(royalty <= 100, "Royalty must be between 0 and 100");
@@ -89,6 +193,9 @@ impl MemeFiContract {
             !self.memes.contains_key(&id),
             "Meme ID already exists"
         );
+        if let Some(cid) = &ipfs_cid {
+            validate_ipfs_cid(cid);
+        }
 
         let meme = MemeNFT {
             id: id.clone(),
@@ -101,9 +208,42 @@ impl MemeFiContract {
             likes_count: 0,
             comments_count: 0,
             last_like_timestamp: 0,
+            ipfs_cid,
         };
         self.memes.insert(&id, &meme);
         self.all_memes.push(&id);
+
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            json!({
+                "standard": "nep171",
+                "version": "1.0.0",
+                "event": "nft_mint",
+                "data": [{"owner_id": meme.owner_id, "token_ids": [meme.id]}]
+            })
+        ));
+    }
+
+    fn meme_to_token(&self, meme: MemeNFT) -> Token {
+        Token {
+            token_id: meme.id,
+            owner_id: meme.owner_id.parse().expect("Invalid owner_id"),
+            metadata: Some(TokenMetadata {
+                title: Some(meme.title),
+                description: Some(meme.description),
+                media: Some(meme.media_url),
+                media_hash: None,
+                copies: Some(1),
+                issued_at: None,
+                expires_at: None,
+                starts_at: None,
+                updated_at: None,
+                extra: None,
+                reference: None,
+                reference_hash: None,
+            }),
+            approved_account_ids: None,
+        }
     }
 
     pub fn get_meme(&self, id: String) -> Option<MemeNFT> {
@@ -140,6 +280,13 @@ impl MemeFiContract {
         self.all_memes.len()
     }
 
+    pub fn get_meme_media(&self, id: String, gateway: String) -> Option<String> {
+        self.memes
+            .get(&id)
+            .and_then(|meme| meme.ipfs_cid)
+            .map(|cid| format!("https://{}/ipfs/{}", gateway, cid))
+    }
+
     pub fn like_meme(&mut self, meme_id: String) {
         let liker = env::predecessor_account_id().to_string();
         let mut meme = self.memes.get(&meme_id).expect("Meme not found");
@@ -227,4 +374,395 @@ impl MemeFiContract {
             0
         }
     }
+
+    pub fn get_comments_paginated(
+        &self,
+        meme_id: String,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Comment> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(50).min(100);
+        let mut result = Vec::new();
+
+        if let Some(comments) = self.comments.get(&meme_id) {
+            for i in from_index..std::cmp::min(from_index + limit, comments.len()) {
+                if let Some(comment) = comments.get(i) {
+                    result.push(comment);
+                }
+            }
         }
+        result
+    }
+
+    pub fn get_comments_count(&self, meme_id: String) -> u64 {
+        self.comments.get(&meme_id).map(|c| c.len()).unwrap_or(0)
+    }
+
+    pub fn get_likers(&self, meme_id: String, from_index: Option<u64>, limit: Option<u64>) -> Vec<String> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(50).min(100);
+
+        if let Some(set) = self.likes.get(&meme_id) {
+            paginate(set.iter().collect(), from_index, limit)
+        } else {
+            vec![]
+        }
+    }
+
+    pub fn list_meme(&mut self, meme_id: String, price: u128) {
+        let caller = env::predecessor_account_id().to_string();
+        let meme = self.memes.get(&meme_id).expect("Meme not found");
+        assert_eq!(meme.owner_id, caller, "Only the owner can list this meme");
+        assert!(price > 0, "Price must be greater than zero");
+
+        self.listings.insert(&meme_id, &price);
+    }
+
+    pub fn unlist_meme(&mut self, meme_id: String) {
+        let caller = env::predecessor_account_id().to_string();
+        let meme = self.memes.get(&meme_id).expect("Meme not found");
+        assert_eq!(meme.owner_id, caller, "Only the owner can unlist this meme");
+
+        self.listings.remove(&meme_id);
+    }
+
+    #[payable]
+    pub fn buy_meme(&mut self, meme_id: String) {
+        let price = self.listings.get(&meme_id).expect("Meme is not listed for sale");
+        let deposit = env::attached_deposit();
+        assert!(deposit >= price, "Attached deposit is less than the listing price");
+
+        let mut meme = self.memes.get(&meme_id).expect("Meme not found");
+        let buyer = env::predecessor_account_id().to_string();
+        assert_ne!(meme.owner_id, buyer, "Owner cannot buy their own meme");
+
+        let royalty_amount = price * meme.royalty as u128 / 100;
+        let seller_amount = price - royalty_amount;
+
+        if royalty_amount > 0 {
+            let creator: AccountId = meme.creator_id.parse().expect("Invalid creator_id");
+            Promise::new(creator).transfer(royalty_amount);
+
+            let mut creator_stats = self.user_stats.get(&meme.creator_id).unwrap_or_default();
+            creator_stats.total_earnings += royalty_amount;
+            self.user_stats.insert(&meme.creator_id, &creator_stats);
+        }
+
+        let seller: AccountId = meme.owner_id.parse().expect("Invalid owner_id");
+        Promise::new(seller).transfer(seller_amount);
+
+        let mut seller_stats = self.user_stats.get(&meme.owner_id).unwrap_or_default();
+        seller_stats.total_earnings += seller_amount;
+        self.user_stats.insert(&meme.owner_id, &seller_stats);
+
+        let old_owner_id = meme.owner_id.clone();
+        meme.owner_id = buyer.clone();
+        self.memes.insert(&meme_id, &meme);
+        self.listings.remove(&meme_id);
+
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            json!({
+                "standard": "nep171",
+                "version": "1.0.0",
+                "event": "nft_transfer",
+                "data": [{
+                    "old_owner_id": old_owner_id,
+                    "new_owner_id": buyer,
+                    "token_ids": [meme_id],
+                    "memo": "meme sale",
+                }]
+            })
+        ));
+
+        let refund = deposit - price;
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+    }
+
+    pub fn get_listing(&self, meme_id: String) -> Option<u128> {
+        self.listings.get(&meme_id)
+    }
+
+    pub fn get_active_listings(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<ListingInfo> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(50).min(100);
+
+        let mut listings = Vec::new();
+        for meme_id in self.all_memes.iter() {
+            if let Some(price) = self.listings.get(&meme_id) {
+                if let Some(meme) = self.memes.get(&meme_id) {
+                    listings.push(ListingInfo { meme, price });
+                }
+            }
+        }
+
+        paginate(listings, from_index, limit)
+    }
+
+    pub fn nft_metadata(&self) -> NFTContractMetadata {
+        NFTContractMetadata {
+            spec: "nft-1.0.0".to_string(),
+            name: "Near-MEME".to_string(),
+            symbol: "MEME".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        }
+    }
+
+    pub fn nft_token(&self, token_id: String) -> Option<Token> {
+        self.memes.get(&token_id).map(|meme| self.meme_to_token(meme))
+    }
+
+    pub fn nft_tokens(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<Token> {
+        self.get_all_memes(from_index, limit)
+            .into_iter()
+            .map(|meme| self.meme_to_token(meme))
+            .collect()
+    }
+
+    pub fn nft_tokens_for_owner(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<Token> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(50).min(100);
+
+        paginate(self.get_user_memes(account_id.to_string()), from_index, limit)
+            .into_iter()
+            .map(|meme| self.meme_to_token(meme))
+            .collect()
+    }
+
+    pub fn nft_total_supply(&self) -> U128 {
+        U128(self.all_memes.len() as u128)
+    }
+
+    #[payable]
+    pub fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: String,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        let _ = approval_id;
+        let sender = env::predecessor_account_id().to_string();
+        let mut meme = self.memes.get(&token_id).expect("Meme not found");
+        assert_eq!(meme.owner_id, sender, "Only the token owner can transfer it");
+        assert_ne!(meme.owner_id, receiver_id.to_string(), "Receiver must differ from current owner");
+
+        let old_owner_id = meme.owner_id.clone();
+        meme.owner_id = receiver_id.to_string();
+        self.memes.insert(&token_id, &meme);
+        self.listings.remove(&token_id);
+
+        env::log_str(&format!(
+            "EVENT_JSON:{}",
+            json!({
+                "standard": "nep171",
+                "version": "1.0.0",
+                "event": "nft_transfer",
+                "data": [{
+                    "old_owner_id": old_owner_id,
+                    "new_owner_id": receiver_id,
+                    "token_ids": [token_id],
+                    "memo": memo,
+                }]
+            })
+        ));
+    }
+
+    pub fn nft_payout(&self, token_id: String, balance: U128, max_len_payout: Option<u32>) -> Payout {
+        let meme = self.memes.get(&token_id).expect("Meme not found");
+
+        let price: u128 = balance.into();
+        let royalty_amount = price * meme.royalty as u128 / 100;
+        let owner_amount = price - royalty_amount;
+
+        let mut payout = HashMap::new();
+        let owner: AccountId = meme.owner_id.parse().expect("Invalid owner_id");
+        if meme.creator_id == meme.owner_id || royalty_amount == 0 {
+            payout.insert(owner, U128(price));
+        } else {
+            let creator: AccountId = meme.creator_id.parse().expect("Invalid creator_id");
+            payout.insert(creator, U128(royalty_amount));
+            payout.insert(owner, U128(owner_amount));
+        }
+
+        if let Some(max_len_payout) = max_len_payout {
+            assert!(
+                payout.len() as u32 <= max_len_payout,
+                "max_len_payout too small for this payout"
+            );
+        }
+
+        Payout { payout }
+    }
+
+    pub fn search_memes(
+        &self,
+        query: String,
+        match_mode: Option<MatchMode>,
+        case_sensitive: Option<bool>,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<MemeNFT> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(50).min(100);
+        let match_mode = match_mode.unwrap_or_default();
+        let case_sensitive = case_sensitive.unwrap_or(false);
+
+        if query.trim().is_empty() {
+            return vec![];
+        }
+
+        let normalize = |s: &str| if case_sensitive { s.to_string() } else { s.to_lowercase() };
+        let query = normalize(&query);
+
+        let mut matches = Vec::new();
+        for meme_id in self.all_memes.iter() {
+            if let Some(meme) = self.memes.get(&meme_id) {
+                let haystack = normalize(&format!("{} {}", meme.title, meme.description));
+                let is_match = match match_mode {
+                    MatchMode::Exact => haystack == query,
+                    MatchMode::Contains => haystack.contains(&query),
+                    MatchMode::StartsWith => haystack.starts_with(&query),
+                };
+                if is_match {
+                    matches.push(meme);
+                }
+            }
+        }
+
+        paginate(matches, from_index, limit)
+    }
+
+    pub fn get_trending_memes(&self, from_index: Option<u64>, limit: Option<u64>) -> Vec<MemeNFT> {
+        let from_index = from_index.unwrap_or(0);
+        let limit = limit.unwrap_or(50).min(100);
+        const GRAVITY: f64 = 1.8;
+
+        let mut scored: Vec<(f64, MemeNFT)> = Vec::new();
+        for meme_id in self.all_memes.iter() {
+            if let Some(meme) = self.memes.get(&meme_id) {
+                let score = if meme.last_like_timestamp == 0 {
+                    0.0
+                } else {
+                    let engagement = (meme.likes_count + meme.comments_count) as f64;
+                    let age_hours = env::block_timestamp()
+                        .saturating_sub(meme.last_like_timestamp) as f64
+                        / 3.6e12;
+                    engagement / (age_hours + 2.0).powf(GRAVITY)
+                };
+                scored.push((score, meme));
+            }
+        }
+
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+        paginate(scored, from_index, limit)
+            .into_iter()
+            .map(|(_, meme)| meme)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use near_sdk::mock::VmAction;
+    use near_sdk::test_utils::{get_created_receipts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    fn context(predecessor: &str, deposit: u128) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .predecessor_account_id(predecessor.parse().unwrap())
+            .attached_deposit(deposit);
+        builder
+    }
+
+    fn mint(contract: &mut MemeFiContract, creator: &str, id: &str, royalty: u8) {
+        testing_env!(context(creator, 0).build());
+        contract.mint_meme(
+            id.to_string(),
+            "https://example.com/meme.png".to_string(),
+            "title".to_string(),
+            "description".to_string(),
+            royalty,
+            None,
+        );
+    }
+
+    fn transferred_to(receiver_id: &str) -> u128 {
+        get_created_receipts()
+            .into_iter()
+            .filter(|receipt| receipt.receiver_id.as_str() == receiver_id)
+            .flat_map(|receipt| receipt.actions.into_iter())
+            .map(|action| match action {
+                VmAction::Transfer { deposit } => deposit,
+                _ => 0,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn buy_meme_splits_royalty_and_refunds_overpayment() {
+        testing_env!(context("alice.near", 0).build());
+        let mut contract = MemeFiContract::new();
+        mint(&mut contract, "alice.near", "meme-1", 10);
+
+        // Give the meme to bob.near first so creator_id (alice.near) and owner_id
+        // (bob.near) differ, which is what makes the royalty split non-trivial.
+        testing_env!(context("alice.near", 1).build());
+        contract.nft_transfer("bob.near".parse().unwrap(), "meme-1".to_string(), None, None);
+
+        testing_env!(context("bob.near", 0).build());
+        contract.list_meme("meme-1".to_string(), 1_000);
+
+        testing_env!(context("carol.near", 1_500).build());
+        contract.buy_meme("meme-1".to_string());
+
+        let meme = contract.get_meme("meme-1".to_string()).unwrap();
+        assert_eq!(meme.owner_id, "carol.near");
+        assert_eq!(contract.get_listing("meme-1".to_string()), None);
+
+        assert_eq!(transferred_to("alice.near"), 100);
+        assert_eq!(transferred_to("bob.near"), 900);
+        assert_eq!(transferred_to("carol.near"), 500);
+
+        let alice_stats = contract.user_stats.get(&"alice.near".to_string()).unwrap();
+        assert_eq!(alice_stats.total_earnings, 100);
+        let bob_stats = contract.user_stats.get(&"bob.near".to_string()).unwrap();
+        assert_eq!(bob_stats.total_earnings, 900);
+    }
+
+    #[test]
+    fn nft_transfer_clears_a_stale_listing() {
+        testing_env!(context("alice.near", 0).build());
+        let mut contract = MemeFiContract::new();
+        mint(&mut contract, "alice.near", "meme-1", 10);
+
+        testing_env!(context("alice.near", 0).build());
+        contract.list_meme("meme-1".to_string(), 1_000);
+
+        testing_env!(context("alice.near", 1).build());
+        contract.nft_transfer("bob.near".parse().unwrap(), "meme-1".to_string(), None, None);
+
+        assert_eq!(contract.get_listing("meme-1".to_string()), None);
+
+        testing_env!(context("eve.near", 1_000).build());
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            contract.buy_meme("meme-1".to_string());
+        }));
+        assert!(result.is_err(), "buying an unlisted meme must panic");
+    }
+}